@@ -0,0 +1,76 @@
+use crate::TrimmedTrackInfo;
+use futures::stream::{self, StreamExt};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use tokio::process::Command;
+
+// Bound how many yt-dlp processes run at once so archiving a large library
+// doesn't spawn hundreds of them simultaneously.
+const DOWNLOAD_CONCURRENCY: usize = 4;
+
+fn find_yt_dlp() -> Option<PathBuf> {
+    let path_var = std::env::var_os("PATH")?;
+    std::env::split_paths(&path_var)
+        .map(|dir| dir.join("yt-dlp"))
+        .find(|candidate| candidate.is_file())
+}
+
+fn search_query(track: &TrimmedTrackInfo) -> String {
+    let first_artist = track.artist_names.first().cloned().unwrap_or_default();
+    format!("{} {} {}", track.song_name, first_artist, track.album_name)
+}
+
+// `--audio-format mp3` always converts the post-processed output to mp3,
+// so this is the filename yt-dlp will end up writing.
+fn destination_path(download_dir: &Path, track: &TrimmedTrackInfo) -> PathBuf {
+    download_dir.join(format!("{}.mp3", track.track_id))
+}
+
+// `-o` needs a template, not the literal final filename, or yt-dlp treats
+// ".mp3" as part of the pre-conversion name instead of the extension.
+fn output_template(download_dir: &Path, track: &TrimmedTrackInfo) -> PathBuf {
+    download_dir.join(format!("{}.%(ext)s", track.track_id))
+}
+
+async fn download_track(yt_dlp: &Path, download_dir: &Path, track: &TrimmedTrackInfo) {
+    if track.track_id.is_empty() || destination_path(download_dir, track).exists() {
+        return;
+    }
+
+    let status = Command::new(yt_dlp)
+        .arg("-x")
+        .arg("--audio-format")
+        .arg("mp3")
+        .arg("-o")
+        .arg(output_template(download_dir, track))
+        .arg(format!("ytsearch1:{}", search_query(track)))
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .await
+        .unwrap();
+
+    if !status.success() {
+        eprintln!("yt-dlp failed to archive \"{}\"", track.song_name);
+    }
+}
+
+// Resolves each liked track to a search query and shells out to yt-dlp to
+// pull down the audio, so the backup survives a track being pulled from
+// Spotify's catalog later. No-op (with a warning) if yt-dlp isn't on PATH.
+pub(crate) async fn download_tracks(download_dir: &Path, tracks: &[TrimmedTrackInfo]) {
+    let Some(yt_dlp) = find_yt_dlp() else {
+        eprintln!("yt-dlp not found on PATH, skipping audio archival");
+        return;
+    };
+    std::fs::create_dir_all(download_dir).unwrap();
+
+    stream::iter(tracks)
+        .for_each_concurrent(DOWNLOAD_CONCURRENCY, |track| {
+            let yt_dlp = yt_dlp.clone();
+            async move {
+                download_track(&yt_dlp, download_dir, track).await;
+            }
+        })
+        .await;
+}
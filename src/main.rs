@@ -1,18 +1,40 @@
-use futures::stream::TryStreamExt;
-use rspotify::{model::SavedTrack, prelude::*, scopes, AuthCodeSpotify, OAuth};
+use futures::stream::{StreamExt, TryStreamExt};
+use rspotify::{
+    model::{PlayableItem, SavedAlbum, SavedTrack, SimplifiedPlaylist, TrackId},
+    prelude::*,
+    AuthCodeSpotify, OAuth,
+};
 use serde::{Deserialize, Serialize};
-use std::{env::current_dir, fs};
+use std::{
+    collections::{HashMap, HashSet},
+    env::current_dir,
+    fs,
+};
 
-#[derive(Debug, Serialize)]
+mod download;
+mod server;
+mod storage;
+
+// Bound how many playlists we page through at once so a large library doesn't
+// fan out into dozens of simultaneous paginated requests.
+const PLAYLIST_FETCH_CONCURRENCY: usize = 8;
+
+#[derive(Debug, Serialize, Deserialize)]
 struct TrimmedTrackInfo {
-    song_name: String,
+    pub(crate) track_id: String,
+    pub(crate) song_name: String,
     added_at: i64,
-    artist_names: Vec<String>,
-    album_name: String,
+    pub(crate) artist_names: Vec<String>,
+    pub(crate) album_name: String,
 }
 impl TrimmedTrackInfo {
     fn from_saved_track(saved_track: SavedTrack) -> Self {
         let mut val = TrimmedTrackInfo {
+            track_id: saved_track
+                .track
+                .id
+                .map(|id| id.to_string())
+                .unwrap_or_default(),
             song_name: saved_track.track.name,
             added_at: saved_track.added_at.timestamp(),
             artist_names: saved_track
@@ -26,9 +48,53 @@ impl TrimmedTrackInfo {
         val.artist_names.sort();
         val
     }
+
+    fn from_playlist_item(added_at: i64, track: rspotify::model::FullTrack) -> Self {
+        let mut val = TrimmedTrackInfo {
+            track_id: track.id.map(|id| id.to_string()).unwrap_or_default(),
+            song_name: track.name,
+            added_at,
+            artist_names: track
+                .artists
+                .into_iter()
+                .map(|artist| artist.name)
+                .collect(),
+            album_name: track.album.name,
+        };
+        val.artist_names.sort();
+        val
+    }
 }
 
-#[derive(Deserialize)]
+#[derive(Debug, Serialize)]
+struct TrimmedAlbumInfo {
+    album_name: String,
+    added_at: i64,
+    artist_names: Vec<String>,
+}
+impl TrimmedAlbumInfo {
+    fn from_saved_album(saved_album: SavedAlbum) -> Self {
+        let mut val = TrimmedAlbumInfo {
+            album_name: saved_album.album.name,
+            added_at: saved_album.added_at.timestamp(),
+            artist_names: saved_album
+                .album
+                .artists
+                .into_iter()
+                .map(|artist| artist.name)
+                .collect(),
+        };
+        val.artist_names.sort();
+        val
+    }
+}
+
+struct PlaylistSnapshot {
+    playlist_name: String,
+    tracks: Vec<TrimmedTrackInfo>,
+}
+
+#[derive(Deserialize, Clone)]
 struct CredentialsFile {
     spotify_client_id: String,
     spotify_client_secret: String,
@@ -42,7 +108,20 @@ impl CredentialsFile {
     }
 }
 
-async fn get_liked_songs_list(creds: CredentialsFile) -> Vec<TrimmedTrackInfo> {
+async fn authorize(creds: CredentialsFile, restore_mode: bool) -> AuthCodeSpotify {
+    let cache_path = std::env::var("SPOTIFY_TOKEN_CACHE_PATH")
+        .unwrap_or_else(|_| "token_cache.json".to_string());
+    authorize_with_cache_path(creds, restore_mode, std::path::PathBuf::from(cache_path)).await
+}
+
+// Every account we authorize needs its own token cache file, or the second
+// `prompt_for_token`/`refresh_token` call just reuses the first account's
+// cached token instead of prompting for a different login.
+async fn authorize_with_cache_path(
+    creds: CredentialsFile,
+    restore_mode: bool,
+    cache_path: std::path::PathBuf,
+) -> AuthCodeSpotify {
     use rspotify::Credentials;
 
     let creds = Credentials {
@@ -50,23 +129,60 @@ async fn get_liked_songs_list(creds: CredentialsFile) -> Vec<TrimmedTrackInfo> {
         secret: Some(creds.spotify_client_secret),
     };
 
+    let mut scopes: HashSet<String> = [
+        "user-library-read",
+        "playlist-read-private",
+        "playlist-read-collaborative",
+    ]
+    .into_iter()
+    .map(String::from)
+    .collect();
+    if restore_mode {
+        scopes.insert("user-library-modify".to_string());
+    }
+
     let oauth = OAuth {
         redirect_uri: "http://localhost:8888/callback".to_string(),
-        scopes: scopes!("user-library-read"),
+        scopes,
         ..Default::default()
     };
 
-    let spotify = {
-        let mut temp_spotify = AuthCodeSpotify::new(creds, oauth);
+    let config = rspotify::Config {
+        token_cached: true,
+        cache_path,
+        ..Default::default()
+    };
 
-        // Obtaining the access token
-        let url = temp_spotify.get_authorize_url(false).unwrap();
-        // This function requires the `cli` feature enabled.
-        temp_spotify.prompt_for_token(&url).await.unwrap();
-        temp_spotify
+    let mut spotify = AuthCodeSpotify::with_config(creds, oauth, config);
+
+    // Reuse a cached token, only refreshing it if it's actually expired,
+    // rather than forcing a browser round-trip on every run. Fall back to
+    // the interactive prompt when there's no usable cache, or when the
+    // refresh itself fails (e.g. a revoked refresh token).
+    let needs_prompt = match spotify.read_token_cache(true).await {
+        Ok(Some(token)) if !token.is_expired() => {
+            *spotify.token.lock().await.unwrap() = Some(token);
+            false
+        }
+        Ok(Some(token)) => {
+            *spotify.token.lock().await.unwrap() = Some(token);
+            spotify.refresh_token().await.is_err()
+        }
+        _ => true,
     };
 
-    // Executing the futures concurrently
+    if needs_prompt {
+        let url = spotify.get_authorize_url(false).unwrap();
+        // This function requires the `cli` feature enabled.
+        spotify.prompt_for_token(&url).await.unwrap();
+    }
+
+    spotify.write_token_cache().await.unwrap();
+
+    spotify
+}
+
+async fn get_liked_songs_list(spotify: &AuthCodeSpotify) -> Vec<TrimmedTrackInfo> {
     let mut stream = spotify.current_user_saved_tracks(None);
     let mut liked_songs = Vec::new();
     while let Some(item) = stream.try_next().await.unwrap() {
@@ -80,49 +196,250 @@ async fn get_liked_songs_list(creds: CredentialsFile) -> Vec<TrimmedTrackInfo> {
     liked_songs
 }
 
-async fn get_s3_client() -> aws_sdk_s3::Client {
-    use aws_config::meta::region::RegionProviderChain;
-    use aws_sdk_s3::Client;
-    let region_provider = RegionProviderChain::default_provider().or_else("us-east-1");
-    let config = aws_config::from_env().region(region_provider).load().await;
-    Client::new(&config)
+async fn get_saved_albums_list(spotify: &AuthCodeSpotify) -> Vec<TrimmedAlbumInfo> {
+    let mut stream = spotify.current_user_saved_albums(None);
+    let mut saved_albums = Vec::new();
+    while let Some(item) = stream.try_next().await.unwrap() {
+        saved_albums.push(TrimmedAlbumInfo::from_saved_album(item));
+    }
+    saved_albums.sort_by(|a, b| {
+        a.added_at
+            .cmp(&b.added_at)
+            .then(a.album_name.cmp(&b.album_name))
+    });
+    saved_albums
 }
 
-async fn download_current_liked_songs() -> String {
-    let resp = get_s3_client()
-        .await
-        .get_object()
-        .bucket("markaronin-liked-songs")
-        .key("liked-songs.txt")
-        .send()
+async fn get_playlist_track_list(
+    spotify: &AuthCodeSpotify,
+    playlist: &SimplifiedPlaylist,
+) -> Vec<TrimmedTrackInfo> {
+    let mut stream = spotify.playlist_items(playlist.id.clone(), None, None);
+    let mut tracks = Vec::new();
+    while let Some(item) = stream.try_next().await.unwrap() {
+        if let Some(PlayableItem::Track(track)) = item.track {
+            let added_at = item.added_at.map(|time| time.timestamp()).unwrap_or(0);
+            tracks.push(TrimmedTrackInfo::from_playlist_item(added_at, track));
+        }
+    }
+    tracks.sort_by(|a, b| {
+        a.added_at
+            .cmp(&b.added_at)
+            .then(a.song_name.cmp(&b.song_name))
+    });
+    tracks
+}
+
+async fn get_playlist_snapshots(spotify: &AuthCodeSpotify) -> Vec<PlaylistSnapshot> {
+    let playlists: Vec<SimplifiedPlaylist> = spotify
+        .current_user_playlists()
+        .try_collect()
         .await
         .unwrap();
-    let data = resp.body.collect().await;
-    return String::from_utf8(data.unwrap().into_bytes().to_vec()).unwrap();
+
+    // Page through each playlist's tracks concurrently, bounded so a large
+    // library doesn't open dozens of paginated requests at once.
+    let mut snapshots: Vec<PlaylistSnapshot> = futures::stream::iter(playlists.iter())
+        .map(|playlist| async move {
+            let tracks = get_playlist_track_list(spotify, playlist).await;
+            PlaylistSnapshot {
+                playlist_name: playlist.name.clone(),
+                tracks,
+            }
+        })
+        .buffer_unordered(PLAYLIST_FETCH_CONCURRENCY)
+        .collect()
+        .await;
+
+    snapshots.sort_by(|a, b| a.playlist_name.cmp(&b.playlist_name));
+    snapshots
+}
+
+// Pulls the JSON-lines entries for a single "# <header>" section back out of
+// a previously serialized snapshot, so we can diff or restore against it.
+fn parse_track_section(snapshot: &str, header: &str) -> Vec<TrimmedTrackInfo> {
+    let header_line = format!("# {}", header);
+    let mut lines = snapshot.lines();
+    while let Some(line) = lines.next() {
+        if line == header_line {
+            return lines
+                .take_while(|line| !line.starts_with("# "))
+                .filter(|line| !line.is_empty())
+                .map(|line| serde_json::from_str(line).unwrap())
+                .collect();
+        }
+    }
+    Vec::new()
+}
+
+// Re-adds any tracks present in the backed-up Liked Songs section but missing
+// from the live library, e.g. after an accidental un-like.
+async fn restore_missing_liked_songs(spotify: &AuthCodeSpotify, old_snapshot: &str) {
+    let backed_up_songs = parse_track_section(old_snapshot, "Liked Songs");
+    let live_songs = get_liked_songs_list(spotify).await;
+    let live_track_ids: HashSet<&str> = live_songs
+        .iter()
+        .map(|song| song.track_id.as_str())
+        .collect();
+
+    let missing_track_ids: Vec<TrackId> = backed_up_songs
+        .iter()
+        .filter(|song| {
+            !song.track_id.is_empty() && !live_track_ids.contains(song.track_id.as_str())
+        })
+        .filter_map(|song| match TrackId::from_id_or_uri(&song.track_id) {
+            Ok(track_id) => Some(track_id),
+            Err(_) => {
+                eprintln!("Skipping malformed track id in backup: {}", song.track_id);
+                None
+            }
+        })
+        .collect();
+
+    if !missing_track_ids.is_empty() {
+        spotify
+            .current_user_saved_tracks_add(missing_track_ids.iter())
+            .await
+            .unwrap();
+    }
+}
+
+fn serialize_track_section(header: &str, tracks: &[TrimmedTrackInfo]) -> String {
+    let mut section = format!("# {}\n", header);
+    for track in tracks {
+        section.push_str(&serde_json::to_string(track).unwrap());
+        section.push('\n');
+    }
+    section
 }
 
-fn diff_liked_songs(new_liked_songs: &String, old_liked_songs: &String) {
+fn serialize_album_section(header: &str, albums: &[TrimmedAlbumInfo]) -> String {
+    let mut section = format!("# {}\n", header);
+    for album in albums {
+        section.push_str(&serde_json::to_string(album).unwrap());
+        section.push('\n');
+    }
+    section
+}
+
+pub(crate) async fn get_library_snapshot(spotify: &AuthCodeSpotify) -> String {
+    let mut sections = vec![serialize_track_section(
+        "Liked Songs",
+        &get_liked_songs_list(spotify).await,
+    )];
+
+    for playlist in get_playlist_snapshots(spotify).await {
+        sections.push(serialize_track_section(
+            &format!("Playlist: {}", playlist.playlist_name),
+            &playlist.tracks,
+        ));
+    }
+
+    sections.push(serialize_album_section(
+        "Saved Albums",
+        &get_saved_albums_list(spotify).await,
+    ));
+
+    sections.join("")
+}
+
+pub(crate) async fn download_current_liked_songs() -> String {
+    storage::liked_songs_backend().fetch().await
+}
+
+pub(crate) fn format_liked_songs_diff(new_liked_songs: &str, old_liked_songs: &str) -> String {
     use diffy::{create_patch, PatchFormatter};
 
     let patch = create_patch(old_liked_songs, new_liked_songs);
 
     let f = PatchFormatter::new().with_color();
 
-    print!("{}", f.fmt_patch(&patch));
+    f.fmt_patch(&patch).to_string()
 }
 
 async fn upload_liked_songs(new_liked_songs: String) {
-    use aws_sdk_s3::types::ByteStream;
-    let byte_stream = ByteStream::from(new_liked_songs.as_bytes().to_vec());
-    get_s3_client()
-        .await
-        .put_object()
-        .bucket("markaronin-liked-songs")
-        .key("liked-songs.txt")
-        .body(byte_stream)
-        .send()
+    storage::liked_songs_backend().store(new_liked_songs).await
+}
+
+async fn get_liked_songs_map(spotify: &AuthCodeSpotify) -> HashMap<String, TrimmedTrackInfo> {
+    get_liked_songs_list(spotify)
         .await
-        .unwrap();
+        .into_iter()
+        .filter(|song| !song.track_id.is_empty())
+        .map(|song| (song.track_id.clone(), song))
+        .collect()
+}
+
+struct LikedSongsIntersection {
+    shared: Vec<TrimmedTrackInfo>,
+    only_in_first: Vec<TrimmedTrackInfo>,
+    only_in_second: Vec<TrimmedTrackInfo>,
+}
+
+fn intersect_liked_songs(
+    first: HashMap<String, TrimmedTrackInfo>,
+    second: HashMap<String, TrimmedTrackInfo>,
+) -> LikedSongsIntersection {
+    let mut shared = Vec::new();
+    let mut shared_track_ids = HashSet::new();
+    let mut only_in_first = Vec::new();
+    for (track_id, song) in first {
+        if second.contains_key(&track_id) {
+            shared_track_ids.insert(track_id);
+            shared.push(song);
+        } else {
+            only_in_first.push(song);
+        }
+    }
+    let only_in_second = second
+        .into_iter()
+        .filter(|(track_id, _)| !shared_track_ids.contains(track_id))
+        .map(|(_, song)| song)
+        .collect();
+
+    LikedSongsIntersection {
+        shared,
+        only_in_first,
+        only_in_second,
+    }
+}
+
+// Authorizes two accounts in turn and reports which liked songs they share,
+// keyed on the stable track ID rather than fuzzy name matching.
+async fn run_intersect_mode(creds: CredentialsFile) {
+    let first_cache_path = std::env::var("SPOTIFY_TOKEN_CACHE_PATH_1")
+        .unwrap_or_else(|_| "token_cache_1.json".to_string());
+    let second_cache_path = std::env::var("SPOTIFY_TOKEN_CACHE_PATH_2")
+        .unwrap_or_else(|_| "token_cache_2.json".to_string());
+
+    println!("Authorizing the first account...");
+    let first_spotify = authorize_with_cache_path(
+        creds.clone(),
+        false,
+        std::path::PathBuf::from(first_cache_path),
+    )
+    .await;
+    let first_songs = get_liked_songs_map(&first_spotify).await;
+
+    println!("Authorizing the second account...");
+    let second_spotify =
+        authorize_with_cache_path(creds, false, std::path::PathBuf::from(second_cache_path)).await;
+    let second_songs = get_liked_songs_map(&second_spotify).await;
+
+    let intersection = intersect_liked_songs(first_songs, second_songs);
+
+    let new_intersection = serialize_track_section("Liked By Both", &intersection.shared)
+        + &serialize_track_section("Only In First Account", &intersection.only_in_first)
+        + &serialize_track_section("Only In Second Account", &intersection.only_in_second);
+
+    let intersection_backend = storage::intersection_backend();
+    let old_intersection = intersection_backend.fetch().await;
+    print!(
+        "{}",
+        format_liked_songs_diff(&new_intersection, &old_intersection)
+    );
+
+    intersection_backend.store(new_intersection).await;
 }
 
 #[tokio::main]
@@ -130,18 +447,45 @@ async fn main() {
     env_logger::init();
 
     let creds = CredentialsFile::read();
+    let restore_mode = std::env::args().any(|arg| arg == "--restore");
+    let serve_mode = std::env::args().any(|arg| arg == "serve");
+    let intersect_mode = std::env::args().any(|arg| arg == "intersect");
+
+    if intersect_mode {
+        run_intersect_mode(creds).await;
+        return;
+    }
+
+    let spotify = authorize(creds, restore_mode).await;
+
+    if serve_mode {
+        server::serve(spotify).await;
+        return;
+    }
 
     let old_liked_songs = download_current_liked_songs().await;
 
-    let new_liked_songs = get_liked_songs_list(creds)
-        .await
-        .into_iter()
-        .map(|item| serde_json::to_string(&item).unwrap())
-        .collect::<Vec<_>>()
-        .join("\n")
-        + "\n";
+    if restore_mode {
+        restore_missing_liked_songs(&spotify, &old_liked_songs).await;
+        return;
+    }
+
+    let new_liked_songs = get_library_snapshot(&spotify).await;
 
-    diff_liked_songs(&new_liked_songs, &old_liked_songs);
+    print!(
+        "{}",
+        format_liked_songs_diff(&new_liked_songs, &old_liked_songs)
+    );
 
     upload_liked_songs(new_liked_songs).await;
+
+    if std::env::args().any(|arg| arg == "--download") {
+        let download_dir =
+            std::env::var("LIKED_SONGS_DOWNLOAD_DIR").unwrap_or_else(|_| "downloads".to_string());
+        download::download_tracks(
+            std::path::Path::new(&download_dir),
+            &get_liked_songs_list(&spotify).await,
+        )
+        .await;
+    }
 }
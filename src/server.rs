@@ -0,0 +1,38 @@
+use crate::{download_current_liked_songs, format_liked_songs_diff, get_library_snapshot};
+use axum::{extract::State, routing::get, Router};
+use rspotify::AuthCodeSpotify;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+// Keeps the already-authorized Spotify client alive across requests instead
+// of re-authenticating on every hit, mirroring a long-lived service holding
+// a single client behind a lock.
+#[derive(Clone)]
+struct ServerState {
+    spotify: Arc<Mutex<AuthCodeSpotify>>,
+}
+
+pub(crate) async fn serve(spotify: AuthCodeSpotify) {
+    let state = ServerState {
+        spotify: Arc::new(Mutex::new(spotify)),
+    };
+
+    let app = Router::new()
+        .route("/diff", get(get_diff))
+        .route("/snapshot", get(get_snapshot))
+        .with_state(state);
+
+    let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
+    axum::serve(listener, app).await.unwrap();
+}
+
+async fn get_snapshot(State(_state): State<ServerState>) -> String {
+    download_current_liked_songs().await
+}
+
+async fn get_diff(State(state): State<ServerState>) -> String {
+    let spotify = state.spotify.lock().await;
+    let old_snapshot = download_current_liked_songs().await;
+    let new_snapshot = get_library_snapshot(&spotify).await;
+    format_liked_songs_diff(&new_snapshot, &old_snapshot)
+}
@@ -0,0 +1,115 @@
+use async_trait::async_trait;
+use std::path::PathBuf;
+
+// Lets the main backup/diff flow read and write its snapshot somewhere other
+// than S3, so the tool works offline or against a git-tracked file.
+#[async_trait]
+pub(crate) trait StorageBackend {
+    async fn fetch(&self) -> String;
+    async fn store(&self, data: String);
+}
+
+pub(crate) struct S3Backend {
+    bucket: String,
+    key: String,
+}
+impl S3Backend {
+    fn new(bucket: String, key: String) -> Self {
+        S3Backend { bucket, key }
+    }
+
+    async fn client(&self) -> aws_sdk_s3::Client {
+        use aws_config::meta::region::RegionProviderChain;
+        use aws_sdk_s3::Client;
+        let region_provider = RegionProviderChain::default_provider().or_else("us-east-1");
+        let config = aws_config::from_env().region(region_provider).load().await;
+        Client::new(&config)
+    }
+}
+#[async_trait]
+impl StorageBackend for S3Backend {
+    async fn fetch(&self) -> String {
+        let resp = self
+            .client()
+            .await
+            .get_object()
+            .bucket(&self.bucket)
+            .key(&self.key)
+            .send()
+            .await
+            .unwrap();
+        let data = resp.body.collect().await;
+        String::from_utf8(data.unwrap().into_bytes().to_vec()).unwrap()
+    }
+
+    async fn store(&self, data: String) {
+        use aws_sdk_s3::types::ByteStream;
+        let byte_stream = ByteStream::from(data.as_bytes().to_vec());
+        self.client()
+            .await
+            .put_object()
+            .bucket(&self.bucket)
+            .key(&self.key)
+            .body(byte_stream)
+            .send()
+            .await
+            .unwrap();
+    }
+}
+
+pub(crate) struct LocalFileBackend {
+    path: PathBuf,
+}
+impl LocalFileBackend {
+    fn new(path: PathBuf) -> Self {
+        LocalFileBackend { path }
+    }
+}
+#[async_trait]
+impl StorageBackend for LocalFileBackend {
+    async fn fetch(&self) -> String {
+        std::fs::read_to_string(&self.path).unwrap_or_default()
+    }
+
+    async fn store(&self, data: String) {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent).unwrap();
+        }
+        std::fs::write(&self.path, data).unwrap();
+    }
+}
+
+// Picks the storage backend from the environment: `STORAGE_BACKEND=local`
+// reads/writes a local file, anything else (the default) keeps using the S3
+// bucket this tool has always used. `default_key` is both the S3 object key
+// and the local file's default path, so callers that need more than one
+// independent snapshot (e.g. the main backup vs. the intersect report) can
+// get distinct backends by passing distinct keys/env vars.
+fn backend_from_env(
+    default_key: &str,
+    key_env: &str,
+    path_env: &str,
+) -> Box<dyn StorageBackend + Send + Sync> {
+    match std::env::var("STORAGE_BACKEND").as_deref() {
+        Ok("local") => Box::new(LocalFileBackend::new(PathBuf::from(
+            std::env::var(path_env).unwrap_or_else(|_| default_key.to_string()),
+        ))),
+        _ => Box::new(S3Backend::new(
+            std::env::var("STORAGE_BUCKET")
+                .unwrap_or_else(|_| "markaronin-liked-songs".to_string()),
+            std::env::var(key_env).unwrap_or_else(|_| default_key.to_string()),
+        )),
+    }
+}
+
+pub(crate) fn liked_songs_backend() -> Box<dyn StorageBackend + Send + Sync> {
+    backend_from_env("liked-songs.txt", "STORAGE_KEY", "STORAGE_PATH")
+}
+
+pub(crate) fn intersection_backend() -> Box<dyn StorageBackend + Send + Sync> {
+    backend_from_env(
+        "intersection.txt",
+        "STORAGE_INTERSECTION_KEY",
+        "STORAGE_INTERSECTION_PATH",
+    )
+}